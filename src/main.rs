@@ -1,83 +1,308 @@
 //! Top level docs.
 
+use std::collections::HashMap;
 use std::error::Error;
 use std::fmt::{self, Write};
 use std::fs;
 use std::io::Read;
+use std::sync::OnceLock;
 
-use proc_macro2::TokenTree;
-use pulldown_cmark::{Event, Options, Parser, Tag};
+use pulldown_cmark::{CodeBlockKind, Event, LinkType, Options, Parser, Tag};
 use syn::visit::{self, Visit};
-use syn::{AttrStyle, Attribute, ItemFn, ItemMod, Signature};
+use syn::{
+    AttrStyle, Attribute, ImplItem, ItemConst, ItemEnum, ItemFn, ItemImpl, ItemMod, ItemStatic,
+    ItemStruct, ItemTrait, ItemType, Lit, Meta, Signature, TraitItem,
+};
 use syntect::easy::HighlightLines;
 use syntect::highlighting::{Style, ThemeSet};
 use syntect::parsing::SyntaxSet;
 use syntect::util::{as_24_bit_terminal_escaped, LinesWithEndings};
 
+/// Matches the extensions rustdoc itself enables when parsing doc comments.
+fn opts() -> Options {
+    Options::ENABLE_TABLES
+        | Options::ENABLE_FOOTNOTES
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_TASKLISTS
+        | Options::ENABLE_SMART_PUNCTUATION
+}
+
 struct Visitor {
     docs: Vec<Doc>,
+    /// Name of the file being visited, used to report code-block warnings.
+    source_file: String,
 }
 
 #[derive(Debug)]
 enum Doc {
-    FnDoc(FnDoc),
-    ModDoc(ModDoc),
+    Fn(FnDoc),
+    Mod(ModDoc),
+    Item(ItemDoc),
+    Trait(TraitDoc),
+    Impl(ImplDoc),
 }
 
 impl fmt::Display for Doc {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Doc::FnDoc(fn_doc) => write!(f, "{}", fn_doc),
-            Doc::ModDoc(mod_doc) => write!(f, "{}", mod_doc),
+            Doc::Fn(fn_doc) => write!(f, "{}", fn_doc),
+            Doc::Mod(mod_doc) => write!(f, "{}", mod_doc),
+            Doc::Item(item_doc) => write!(f, "{}", item_doc),
+            Doc::Trait(trait_doc) => write!(f, "{}", trait_doc),
+            Doc::Impl(impl_doc) => write!(f, "{}", impl_doc),
+        }
+    }
+}
+
+impl Doc {
+    /// The item's own raw doc-comment markdown, for gathering headings into
+    /// a table of contents.
+    fn doc_text(&self) -> &str {
+        match self {
+            Doc::Fn(fn_doc) => &fn_doc.doc,
+            Doc::Mod(mod_doc) => &mod_doc.doc,
+            Doc::Item(item_doc) => &item_doc.doc,
+            Doc::Trait(trait_doc) => &trait_doc.doc,
+            Doc::Impl(impl_doc) => &impl_doc.doc,
         }
     }
 }
 
-/// Does some basic markdown parsing so we can get to the codeblocks.
+/// Centers a heading in a dashed bar, matching how every `Doc` variant
+/// separates itself from the next in the terminal output.
+fn heading(text: &str) -> String {
+    format!("{:-^1$}", text, 80)
+}
+
+/// Picks the syntect syntax token for a fenced code-block infostring.
+///
+/// rustdoc infostrings mix a handful of directives (`ignore`, `should_panic`,
+/// `no_run`, `compile_fail`, `edition2015`/`2018`/`2021`) in with the actual
+/// language, and assume `rust` when nothing else is given. This pulls out the
+/// first token that isn't one of those directives.
+fn code_block_language(info: &str) -> Option<String> {
+    info.split(|c: char| c == ',' || c.is_whitespace())
+        .map(str::trim)
+        .find(|token| {
+            !token.is_empty()
+                && !matches!(
+                    *token,
+                    "should_panic"
+                        | "ignore"
+                        | "no_run"
+                        | "compile_fail"
+                        | "edition2015"
+                        | "edition2018"
+                        | "edition2021"
+                        | "rust"
+                )
+        })
+        .map(str::to_string)
+}
+
+/// Strips rustdoc's hidden-line convention from a Rust code block.
+///
+/// A line whose first non-whitespace characters are `# ` (or that is just a
+/// bare `#`) is boilerplate that rustdoc hides from the rendered example; a
+/// leading `##` escapes that rule and collapses to a literal `#`.
+fn strip_hidden_lines(code: &str) -> String {
+    let mut out = String::new();
+    for line in code.lines() {
+        let trimmed = line.trim_start();
+        if trimmed == "#" || trimmed.starts_with("# ") {
+            continue;
+        }
+        if let Some(rest) = trimmed.strip_prefix("##") {
+            let indent = &line[..line.len() - trimmed.len()];
+            out.push_str(indent);
+            out.push('#');
+            out.push_str(rest);
+        } else {
+            out.push_str(line);
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// Default theme name, used until `set_theme` picks a different one.
+const DEFAULT_THEME: &str = "base16-ocean.dark";
+
+static SYNTAX_SET: OnceLock<SyntaxSet> = OnceLock::new();
+static THEME_SET: OnceLock<ThemeSet> = OnceLock::new();
+static THEME_NAME: OnceLock<String> = OnceLock::new();
+
+/// The shared syntax set, loaded once per run rather than per code block.
+fn syntax_set() -> &'static SyntaxSet {
+    SYNTAX_SET.get_or_init(SyntaxSet::load_defaults_newlines)
+}
+
+/// The shared theme set, loaded once per run rather than per code block.
+fn theme_set() -> &'static ThemeSet {
+    THEME_SET.get_or_init(ThemeSet::load_defaults)
+}
+
+/// Selects the theme used by every subsequent call to `highlight`. Must be
+/// called at most once, before any rendering happens; errors (and lists the
+/// themes that are actually available) if `name` isn't in the theme set.
+fn set_theme(name: String) -> Result<(), String> {
+    if !theme_set().themes.contains_key(&name) {
+        let mut available: Vec<&str> = theme_set().themes.keys().map(String::as_str).collect();
+        available.sort_unstable();
+        return Err(format!(
+            "unknown theme {:?}, available themes: {}",
+            name,
+            available.join(", ")
+        ));
+    }
+    let _ = THEME_NAME.set(name);
+    Ok(())
+}
+
+fn theme_name() -> &'static str {
+    THEME_NAME
+        .get()
+        .map(String::as_str)
+        .unwrap_or(DEFAULT_THEME)
+}
+
+/// Highlights `code` with syntect, picking the syntax for `lang` (a syntect
+/// token or file extension) and falling back to Rust when absent.
+fn highlight(code: &str, lang: Option<&str>) -> String {
+    let ps = syntax_set();
+    let ts = theme_set();
+
+    let syntax = lang
+        .and_then(|lang| {
+            ps.find_syntax_by_token(lang)
+                .or_else(|| ps.find_syntax_by_extension(lang))
+        })
+        .unwrap_or_else(|| ps.find_syntax_by_extension("rs").unwrap());
+
+    let mut h = HighlightLines::new(syntax, &ts.themes[theme_name()]);
+    let mut out = String::new();
+    for line in LinesWithEndings::from(code) {
+        // LinesWithEndings enables use of newlines mode
+        let ranges: Vec<(Style, &str)> = h.highlight(line, &ps);
+        let escaped = as_24_bit_terminal_escaped(&ranges[..], true);
+        write!(out, "{}", escaped).unwrap();
+    }
+    write!(out, "\x1b[0m").unwrap();
+    out
+}
+
+/// Renders a full pulldown-cmark event stream as ANSI-styled terminal output.
+///
+/// This is the core rendering engine of the crate: every event the parser can
+/// produce for rustdoc-flavored markdown (tables, footnotes, strikethrough,
+/// task lists, smart punctuation, ...) needs a real rendering, not just the
+/// handful of events a trivial doc comment happens to exercise.
 fn format_markdown(input: &str) -> String {
-    let parser = Parser::new_ext(input, Options::empty());
+    let parser = Parser::new_ext(input, opts());
 
     let mut out = String::new();
     let mut code = String::new();
     let mut is_code = false;
 
+    // Depth of nested lists; `Some(n)` is the next number for an ordered
+    // list, `None` means the innermost list is unordered.
+    let mut list_stack: Vec<Option<u64>> = Vec::new();
+    // Destination URLs for links/images we're currently inside of.
+    let mut link_stack: Vec<String> = Vec::new();
+    // Language token of the fenced code block we're currently inside of.
+    let mut code_lang: Option<String> = None;
+
     for event in parser {
         match event {
             Event::Start(tag) => match tag {
-                Tag::CodeBlock(_code_block_kind) => is_code = true,
+                Tag::CodeBlock(code_block_kind) => {
+                    is_code = true;
+                    code_lang = match code_block_kind {
+                        CodeBlockKind::Fenced(info) => code_block_language(&info),
+                        CodeBlockKind::Indented => None,
+                    };
+                }
                 Tag::Paragraph => {}
                 Tag::Heading(level) => {
                     write!(out, "\n\n{:#>1$} ", "", level as usize).unwrap();
                 }
-                _ => todo!("{:?}", tag),
-            },
-            Event::End(tag) => {
-                match tag {
-                    Tag::CodeBlock { .. } => {
-                        // Indented
-                        // Fenced(CowStr<'a>)
-                        is_code = false;
-
-                        // TODO: Highlight!
-                        let ps = SyntaxSet::load_defaults_newlines();
-                        let ts = ThemeSet::load_defaults();
-
-                        let syntax = ps.find_syntax_by_extension("rs").unwrap();
-                        let mut h = HighlightLines::new(syntax, &ts.themes["base16-ocean.dark"]);
-                        for line in LinesWithEndings::from(&code) {
-                            // LinesWithEndings enables use of newlines mode
-                            let ranges: Vec<(Style, &str)> = h.highlight(line, &ps);
-                            let escaped = as_24_bit_terminal_escaped(&ranges[..], true);
-                            write!(out, "{}", escaped).unwrap();
+                Tag::BlockQuote => write!(out, "\x1b[2m\u{2503} ").unwrap(),
+                Tag::List(start) => list_stack.push(start),
+                Tag::Item => {
+                    let depth = list_stack.len().saturating_sub(1);
+                    write!(out, "\n{:indent$}", "", indent = depth * 2).unwrap();
+                    match list_stack.last_mut() {
+                        Some(Some(n)) => {
+                            write!(out, "{}. ", n).unwrap();
+                            *n += 1;
                         }
-                        write!(out, "\x1b[0m\n\n").unwrap();
-                        code.clear();
+                        _ => write!(out, "- ").unwrap(),
                     }
-                    Tag::Heading(_level) => write!(out, "\n\n").unwrap(),
-                    Tag::Paragraph => write!(code, "\n\n").unwrap(),
-                    _ => todo!("{:?}", tag),
                 }
-            }
+                Tag::FootnoteDefinition(label) => write!(out, "\n[^{}]: ", label).unwrap(),
+                Tag::Table(_alignments) => write!(out, "\n\n").unwrap(),
+                Tag::TableHead => {}
+                Tag::TableRow => writeln!(out).unwrap(),
+                Tag::TableCell => write!(out, "| ").unwrap(),
+                Tag::Emphasis => write!(out, "\x1b[3m").unwrap(),
+                Tag::Strong => write!(out, "\x1b[1m").unwrap(),
+                Tag::Strikethrough => write!(out, "\x1b[9m").unwrap(),
+                Tag::Link(link_type, url, _title) => {
+                    if link_type != LinkType::Autolink {
+                        write!(out, "[").unwrap();
+                    }
+                    link_stack.push(url.to_string());
+                }
+                Tag::Image(_link_type, url, _title) => {
+                    write!(out, "![").unwrap();
+                    link_stack.push(url.to_string());
+                }
+            },
+            Event::End(tag) => match tag {
+                Tag::CodeBlock { .. } => {
+                    is_code = false;
+
+                    let rendered = if code_lang.is_none() {
+                        strip_hidden_lines(&code)
+                    } else {
+                        code.clone()
+                    };
+
+                    write!(out, "{}\n\n", highlight(&rendered, code_lang.as_deref())).unwrap();
+                    code.clear();
+                    code_lang = None;
+                }
+                Tag::Heading(_level) => write!(out, "\n\n").unwrap(),
+                Tag::Paragraph => write!(out, "\n\n").unwrap(),
+                Tag::BlockQuote => write!(out, "\x1b[0m\n\n").unwrap(),
+                Tag::List(_start) => {
+                    list_stack.pop();
+                    if list_stack.is_empty() {
+                        write!(out, "\n\n").unwrap();
+                    }
+                }
+                Tag::Item => {}
+                Tag::FootnoteDefinition(_label) => writeln!(out).unwrap(),
+                Tag::Table(_alignments) => write!(out, "\n\n").unwrap(),
+                Tag::TableHead => writeln!(out, "|").unwrap(),
+                Tag::TableRow => write!(out, "|").unwrap(),
+                Tag::TableCell => write!(out, " ").unwrap(),
+                Tag::Emphasis => write!(out, "\x1b[0m").unwrap(),
+                Tag::Strong => write!(out, "\x1b[0m").unwrap(),
+                Tag::Strikethrough => write!(out, "\x1b[0m").unwrap(),
+                Tag::Link(link_type, _url, _title) => {
+                    let url = link_stack.pop().unwrap_or_default();
+                    // Autolinks already wrote the URL as their own `Text`
+                    // event; printing it again here would double it up.
+                    if link_type != LinkType::Autolink {
+                        write!(out, "]({})", url).unwrap();
+                    }
+                }
+                Tag::Image(_link_type, _url, _title) => {
+                    let url = link_stack.pop().unwrap_or_default();
+                    write!(out, "]({})", url).unwrap();
+                }
+            },
             Event::Text(text) => {
                 if is_code {
                     write!(code, "{}", text).unwrap();
@@ -85,13 +310,15 @@ fn format_markdown(input: &str) -> String {
                     write!(out, "{}", text).unwrap();
                 }
             }
-            Event::Code(_) => todo!(),
-            Event::Html(_) => todo!(),
-            Event::FootnoteReference(_) => todo!(),
-            Event::SoftBreak => todo!(),
-            Event::HardBreak => todo!(),
-            Event::Rule => todo!(),
-            Event::TaskListMarker(_) => todo!(),
+            Event::Code(text) => write!(out, "\x1b[7m {} \x1b[0m", text).unwrap(),
+            Event::Html(html) => write!(out, "{}", html).unwrap(),
+            Event::FootnoteReference(label) => write!(out, "[^{}]", label).unwrap(),
+            Event::SoftBreak => writeln!(out).unwrap(),
+            Event::HardBreak => write!(out, "\n\n").unwrap(),
+            Event::Rule => write!(out, "\n\n{}\n\n", "\u{2500}".repeat(80)).unwrap(),
+            Event::TaskListMarker(checked) => {
+                write!(out, "{} ", if checked { "[x]" } else { "[ ]" }).unwrap()
+            }
         }
     }
     out
@@ -105,7 +332,7 @@ struct FnDoc {
 
 impl fmt::Display for FnDoc {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let hl = format!("{:-^1$}", "function", 80); // TODO: Useful heading
+        let hl = heading("function"); // TODO: Useful heading
 
         write!(
             f,
@@ -125,19 +352,274 @@ struct ModDoc {
 
 impl fmt::Display for ModDoc {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let hl = format!("{:-^1$}", format!("module {}", self.ident), 80);
+        let hl = heading(&format!("module {}", self.ident));
         write!(f, "{}\n\n{}\n\n", hl, format_markdown(&self.doc))
     }
 }
 
-/// Formats a syn::Signature into a human readabable signature.
-///
-/// ## TODO: Output other parts of the sigature including return types, types, and where clauses.
-fn format_signature(sig: &Signature) -> String {
-    format!("fn {}()\n\n", &sig.ident)
+/// A documented struct, enum, const, static, or type alias: just a heading
+/// and its own doc comment, with no associated items to nest underneath it.
+#[derive(Debug)]
+struct ItemDoc {
+    kind: &'static str,
+    ident: String,
+    doc: String,
+}
+
+impl fmt::Display for ItemDoc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let hl = heading(&format!("{} {}", self.kind, self.ident));
+        write!(f, "{}\n\n{}\n\n", hl, format_markdown(&self.doc))
+    }
+}
+
+#[derive(Debug)]
+struct TraitDoc {
+    ident: String,
+    doc: String,
+    /// Pre-rendered, indented docs for the trait's associated methods.
+    items: String,
+}
+
+impl fmt::Display for TraitDoc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let hl = heading(&format!("trait {}", self.ident));
+        write!(
+            f,
+            "{}\n\n{}\n\n{}",
+            hl,
+            format_markdown(&self.doc),
+            self.items
+        )
+    }
+}
+
+#[derive(Debug)]
+struct ImplDoc {
+    heading: String,
+    doc: String,
+    /// Pre-rendered, indented docs for the impl's associated methods.
+    items: String,
+}
+
+impl fmt::Display for ImplDoc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let hl = heading(&self.heading);
+        write!(
+            f,
+            "{}\n\n{}\n\n{}",
+            hl,
+            format_markdown(&self.doc),
+            self.items
+        )
+    }
 }
 
-fn format_doc(attrs: &[Attribute]) -> String {
+/// Formats a visibility qualifier, e.g. `pub `, `pub(crate) `, or nothing for
+/// a private (inherited) item.
+fn format_visibility(vis: &syn::Visibility) -> String {
+    match vis {
+        syn::Visibility::Public(_) => "pub ".to_string(),
+        syn::Visibility::Crate(_) => "crate ".to_string(),
+        syn::Visibility::Restricted(restricted) => {
+            let path = format_path(&restricted.path);
+            if restricted.in_token.is_some() {
+                format!("pub(in {}) ", path)
+            } else {
+                format!("pub({}) ", path)
+            }
+        }
+        syn::Visibility::Inherited => String::new(),
+    }
+}
+
+/// Formats a single function argument, `&self`/`mut x: T`/etc.
+fn format_fn_arg(arg: &syn::FnArg) -> String {
+    match arg {
+        syn::FnArg::Receiver(receiver) => {
+            let mut out = String::new();
+            if let Some((_, lifetime)) = &receiver.reference {
+                out.push('&');
+                if let Some(lifetime) = lifetime {
+                    write!(out, "{} ", lifetime).unwrap();
+                }
+            }
+            if receiver.mutability.is_some() {
+                out.push_str("mut ");
+            }
+            out.push_str("self");
+            out
+        }
+        syn::FnArg::Typed(pat_type) => {
+            format!(
+                "{}: {}",
+                format_pat(&pat_type.pat),
+                format_type(&pat_type.ty)
+            )
+        }
+    }
+}
+
+/// Formats the binding pattern of a typed function argument.
+fn format_pat(pat: &syn::Pat) -> String {
+    match pat {
+        syn::Pat::Ident(pat_ident) => {
+            let mut out = String::new();
+            if pat_ident.by_ref.is_some() {
+                out.push_str("ref ");
+            }
+            if pat_ident.mutability.is_some() {
+                out.push_str("mut ");
+            }
+            out.push_str(&pat_ident.ident.to_string());
+            out
+        }
+        _ => "_".to_string(),
+    }
+}
+
+/// Formats a single trait bound, e.g. `Display` or the lifetime `'a`.
+fn format_type_param_bound(bound: &syn::TypeParamBound) -> String {
+    match bound {
+        syn::TypeParamBound::Trait(trait_bound) => format_path(&trait_bound.path),
+        syn::TypeParamBound::Lifetime(lifetime) => lifetime.to_string(),
+    }
+}
+
+/// Formats a function or impl's generic parameter list (without the angle
+/// brackets), e.g. `'a, T: Display, const N: usize`.
+fn format_generic_params(generics: &syn::Generics) -> String {
+    generics
+        .params
+        .iter()
+        .map(|param| match param {
+            syn::GenericParam::Type(type_param) => {
+                let mut out = type_param.ident.to_string();
+                if !type_param.bounds.is_empty() {
+                    let bounds = type_param
+                        .bounds
+                        .iter()
+                        .map(format_type_param_bound)
+                        .collect::<Vec<_>>()
+                        .join(" + ");
+                    write!(out, ": {}", bounds).unwrap();
+                }
+                out
+            }
+            syn::GenericParam::Lifetime(lifetime_def) => lifetime_def.lifetime.to_string(),
+            syn::GenericParam::Const(const_param) => {
+                format!(
+                    "const {}: {}",
+                    const_param.ident,
+                    format_type(&const_param.ty)
+                )
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Formats a `where` clause, e.g. `where T: Display, 'a: 'b`.
+fn format_where_clause(where_clause: &syn::WhereClause) -> String {
+    let predicates = where_clause
+        .predicates
+        .iter()
+        .map(|predicate| match predicate {
+            syn::WherePredicate::Type(predicate) => {
+                let bounds = predicate
+                    .bounds
+                    .iter()
+                    .map(format_type_param_bound)
+                    .collect::<Vec<_>>()
+                    .join(" + ");
+                format!("{}: {}", format_type(&predicate.bounded_ty), bounds)
+            }
+            syn::WherePredicate::Lifetime(predicate) => {
+                let bounds = predicate
+                    .bounds
+                    .iter()
+                    .map(|lifetime| lifetime.to_string())
+                    .collect::<Vec<_>>()
+                    .join(" + ");
+                format!("{}: {}", predicate.lifetime, bounds)
+            }
+            syn::WherePredicate::Eq(predicate) => {
+                format!(
+                    "{} = {}",
+                    format_type(&predicate.lhs_ty),
+                    format_type(&predicate.rhs_ty)
+                )
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+    format!("where {}", predicates)
+}
+
+/// Formats a syn::Signature (plus its item's visibility) into a complete,
+/// highlighted signature: qualifiers, generics, the typed argument list, the
+/// return type, and any where clause, matching how rustdoc surfaces a
+/// highlighted signature at the top of each item.
+fn format_signature(vis: &syn::Visibility, sig: &Signature) -> String {
+    let mut code = format_visibility(vis);
+
+    if sig.constness.is_some() {
+        code.push_str("const ");
+    }
+    if sig.asyncness.is_some() {
+        code.push_str("async ");
+    }
+    if sig.unsafety.is_some() {
+        code.push_str("unsafe ");
+    }
+    if let Some(abi) = &sig.abi {
+        code.push_str("extern ");
+        match &abi.name {
+            Some(name) => write!(code, "{:?} ", name.value()).unwrap(),
+            None => code.push_str("\"Rust\" "),
+        }
+    }
+
+    write!(code, "fn {}", sig.ident).unwrap();
+    if !sig.generics.params.is_empty() {
+        write!(code, "<{}>", format_generic_params(&sig.generics)).unwrap();
+    }
+
+    let args = sig
+        .inputs
+        .iter()
+        .map(format_fn_arg)
+        .collect::<Vec<_>>()
+        .join(", ");
+    write!(code, "({})", args).unwrap();
+
+    if let syn::ReturnType::Type(_, ty) = &sig.output {
+        write!(code, " -> {}", format_type(ty)).unwrap();
+    }
+
+    if let Some(where_clause) = &sig.generics.where_clause {
+        write!(code, "\n{}", format_where_clause(where_clause)).unwrap();
+    }
+
+    format_declaration(&code)
+}
+
+/// Highlights a one-off declaration line (an associated const or type
+/// alias) the same way a full signature is highlighted.
+fn format_declaration(code: &str) -> String {
+    format!("{}\n\n", highlight(code, None))
+}
+
+/// The joined text of an item's doc comments, plus enough bookkeeping to map
+/// a line of that text back to the source line it came from.
+struct DocText {
+    text: String,
+    /// `line_map[i]` is the source line of the attribute that produced line
+    /// `i` (0-indexed) of `text`.
+    line_map: Vec<usize>,
+}
+
+fn format_doc(attrs: &[Attribute]) -> DocText {
     // The compiler transforms doc comments, such as /// comment and /*! comment */, into
     // attributes before macros are expanded. Each comment is expanded into an attribute of the
     // form #[doc = r"comment"].
@@ -146,33 +628,236 @@ fn format_doc(attrs: &[Attribute]) -> String {
     // Inner doc comments like //! Example.
 
     let mut doc = String::new();
+    let mut line_map = Vec::new();
+
     for attr in attrs {
-        if attr.style == AttrStyle::Outer {
-            for token in attr.tokens.clone().into_iter() {
-                match token {
-                    TokenTree::Literal(lit) => {
-                        let mut lit = lit.to_string();
-                        lit.remove(0); // remove the first `"`
-                        lit.remove(0); // assume there is a leading space (TODO: Fix this assumption)
-                        if !lit.is_empty() {
-                            lit.remove(lit.len() - 1); // remove the last `"`
-                        }
-                        write!(doc, "{}\n", lit).unwrap();
-                    }
-                    _ => (),
+        if attr.style != AttrStyle::Outer {
+            continue;
+        }
+        let lit = match attr.parse_meta() {
+            Ok(Meta::NameValue(nv)) if nv.path.is_ident("doc") => match nv.lit {
+                Lit::Str(lit) => lit,
+                _ => continue,
+            },
+            _ => continue,
+        };
+
+        // `LitStr::value` gives us the real, unescaped string regardless of
+        // whether the compiler wrote it out as `"..."` or a raw `r"..."`
+        // literal (block doc comments containing a `"` use the latter).
+        let source_line = lit.span().start().line;
+        let value = lit.value();
+        let value = value.strip_prefix(' ').unwrap_or(&value);
+
+        for line in value.split('\n') {
+            doc.push_str(line);
+            doc.push('\n');
+            line_map.push(source_line);
+        }
+    }
+
+    DocText {
+        text: doc,
+        line_map,
+    }
+}
+
+/// Parses every Rust code block in `doc` and warns, rather than panicking or
+/// silently mis-highlighting, when one fails to parse.
+///
+/// Code blocks are found with the same parser options `format_markdown` uses,
+/// but via `Parser::into_offset_iter`, so a byte offset into `doc.text` can be turned
+/// back into an approximate source line by counting newlines up to it and
+/// looking the resulting line index up in `doc.line_map`.
+fn validate_doc_code_blocks(file: &str, doc: &DocText) {
+    let parser = Parser::new_ext(&doc.text, opts()).into_offset_iter();
+
+    let mut code = String::new();
+    let mut block_start: Option<usize> = None;
+
+    for (event, range) in parser {
+        match event {
+            Event::Start(Tag::CodeBlock(kind)) => {
+                let is_rust = match &kind {
+                    CodeBlockKind::Indented => true,
+                    CodeBlockKind::Fenced(info) => code_block_language(info).is_none(),
+                };
+                if is_rust {
+                    code.clear();
+                    block_start = Some(range.start);
+                }
+            }
+            Event::Text(text) if block_start.is_some() => code.push_str(&text),
+            Event::End(Tag::CodeBlock(_)) => {
+                if let Some(start) = block_start.take() {
+                    check_rust_block(file, doc, &code, start);
                 }
             }
+            _ => {}
         }
     }
-    doc
+}
+
+fn check_rust_block(file: &str, doc: &DocText, code: &str, start_offset: usize) {
+    let snippet = strip_hidden_lines(code);
+
+    // Most examples are a handful of statements, not a full set of items, so
+    // a snippet that doesn't parse on its own is tried again wrapped in a
+    // function body, the same way rustdoc wraps a doctest in `fn main() {
+    // ... }` before compiling it.
+    if syn::parse_file(&snippet).is_ok() {
+        return;
+    }
+    let wrapped = format!("fn __doctest() {{\n{}\n}}", snippet);
+    if syn::parse_file(&wrapped).is_ok() {
+        return;
+    }
+
+    let line_index = doc.text[..start_offset].matches('\n').count();
+    let source_line = doc.line_map.get(line_index).copied().unwrap_or(0);
+    let first_line = snippet
+        .lines()
+        .find(|line| !line.trim().is_empty())
+        .unwrap_or("");
+    eprintln!(
+        "warning: {}:{}: doc example failed to parse: {}",
+        file,
+        source_line,
+        first_line.trim()
+    );
+}
+
+/// Joins a path's segments with `::`, keeping each segment's generic
+/// arguments (e.g. `From<i32>`) so that e.g. two differently-specialized
+/// trait impls don't render with identical headings.
+fn format_path(path: &syn::Path) -> String {
+    path.segments
+        .iter()
+        .map(|segment| {
+            let ident = segment.ident.to_string();
+            match &segment.arguments {
+                syn::PathArguments::AngleBracketed(args) => {
+                    let inner = args
+                        .args
+                        .iter()
+                        .map(format_generic_arg)
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    format!("{}<{}>", ident, inner)
+                }
+                _ => ident,
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("::")
+}
+
+/// Formats a generic argument, e.g. the `T` or `'a` in `Vec<T>`/`Foo<'a>`.
+fn format_generic_arg(arg: &syn::GenericArgument) -> String {
+    match arg {
+        syn::GenericArgument::Lifetime(lifetime) => lifetime.to_string(),
+        syn::GenericArgument::Type(ty) => format_type(ty),
+        _ => "_".to_string(),
+    }
+}
+
+/// Formats a type well enough for a signature or `impl` heading: named paths
+/// (with their generic arguments), references, pointers, tuples and slices.
+/// Anything more exotic falls back to a placeholder.
+fn format_type(ty: &syn::Type) -> String {
+    match ty {
+        syn::Type::Path(type_path) => format_path(&type_path.path),
+        syn::Type::Reference(reference) => {
+            let mut out = String::from("&");
+            if let Some(lifetime) = &reference.lifetime {
+                write!(out, "{} ", lifetime).unwrap();
+            }
+            if reference.mutability.is_some() {
+                out.push_str("mut ");
+            }
+            out.push_str(&format_type(&reference.elem));
+            out
+        }
+        syn::Type::Ptr(ptr) => {
+            let mutability = if ptr.mutability.is_some() {
+                "mut"
+            } else {
+                "const"
+            };
+            format!("*{} {}", mutability, format_type(&ptr.elem))
+        }
+        syn::Type::Slice(slice) => format!("[{}]", format_type(&slice.elem)),
+        syn::Type::Tuple(tuple) => format!(
+            "({})",
+            tuple
+                .elems
+                .iter()
+                .map(format_type)
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        _ => "_".to_string(),
+    }
+}
+
+/// Indents every non-empty line of `text` by `prefix`, for nesting an
+/// associated item's rendered docs underneath its parent trait or impl.
+fn indent_lines(text: &str, prefix: &str) -> String {
+    let mut out = String::new();
+    for line in text.lines() {
+        if !line.is_empty() {
+            out.push_str(prefix);
+            out.push_str(line);
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// Renders a single associated method's signature and docs, indented for
+/// nesting underneath its parent trait or impl block.
+fn render_nested_method(
+    source_file: &str,
+    vis: &syn::Visibility,
+    sig: &Signature,
+    attrs: &[Attribute],
+) -> String {
+    render_nested_decl(source_file, &format_signature(vis, sig), attrs)
+}
+
+/// Renders a single associated const or type alias's declaration and docs,
+/// indented the same way as a nested method.
+fn render_nested_decl(source_file: &str, declaration: &str, attrs: &[Attribute]) -> String {
+    let doc = format_doc(attrs);
+    validate_doc_code_blocks(source_file, &doc);
+
+    let block = format!("{}{}\n", declaration, format_markdown(&doc.text));
+    indent_lines(&block, "    ")
+}
+
+impl Visitor {
+    fn push_item_doc(&mut self, kind: &'static str, ident: &syn::Ident, attrs: &[Attribute]) {
+        let doc = format_doc(attrs);
+        validate_doc_code_blocks(&self.source_file, &doc);
+
+        self.docs.push(Doc::Item(ItemDoc {
+            kind,
+            ident: ident.to_string(),
+            doc: doc.text,
+        }));
+    }
 }
 
 impl<'ast> Visit<'ast> for Visitor {
     fn visit_item_fn(&mut self, node: &'ast ItemFn) {
-        let signature = format_signature(&node.sig);
+        let signature = format_signature(&node.vis, &node.sig);
         let doc = format_doc(&node.attrs);
+        validate_doc_code_blocks(&self.source_file, &doc);
 
-        self.docs.push(Doc::FnDoc(FnDoc { signature, doc }));
+        self.docs.push(Doc::Fn(FnDoc {
+            signature,
+            doc: doc.text,
+        }));
 
         // Delegate to the default impl to visit any nested functions.
         visit::visit_item_fn(self, node);
@@ -181,10 +866,142 @@ impl<'ast> Visit<'ast> for Visitor {
     fn visit_item_mod(&mut self, node: &'ast ItemMod) {
         let ident = format!("{}", &node.ident);
         let doc = format_doc(&node.attrs);
-        self.docs.push(Doc::ModDoc(ModDoc { ident, doc }));
+        validate_doc_code_blocks(&self.source_file, &doc);
+
+        self.docs.push(Doc::Mod(ModDoc {
+            ident,
+            doc: doc.text,
+        }));
 
         visit::visit_item_mod(self, node);
     }
+
+    fn visit_item_struct(&mut self, node: &'ast ItemStruct) {
+        self.push_item_doc("struct", &node.ident, &node.attrs);
+        visit::visit_item_struct(self, node);
+    }
+
+    fn visit_item_enum(&mut self, node: &'ast ItemEnum) {
+        self.push_item_doc("enum", &node.ident, &node.attrs);
+        visit::visit_item_enum(self, node);
+    }
+
+    fn visit_item_const(&mut self, node: &'ast ItemConst) {
+        self.push_item_doc("const", &node.ident, &node.attrs);
+        visit::visit_item_const(self, node);
+    }
+
+    fn visit_item_static(&mut self, node: &'ast ItemStatic) {
+        self.push_item_doc("static", &node.ident, &node.attrs);
+        visit::visit_item_static(self, node);
+    }
+
+    fn visit_item_type(&mut self, node: &'ast ItemType) {
+        self.push_item_doc("type", &node.ident, &node.attrs);
+        visit::visit_item_type(self, node);
+    }
+
+    fn visit_item_trait(&mut self, node: &'ast ItemTrait) {
+        let ident = node.ident.to_string();
+        let doc = format_doc(&node.attrs);
+        validate_doc_code_blocks(&self.source_file, &doc);
+
+        let items = node
+            .items
+            .iter()
+            .filter_map(|item| match item {
+                TraitItem::Method(method) => Some(render_nested_method(
+                    &self.source_file,
+                    &syn::Visibility::Inherited,
+                    &method.sig,
+                    &method.attrs,
+                )),
+                TraitItem::Const(item) => {
+                    let decl = format!("const {}: {};", item.ident, format_type(&item.ty));
+                    Some(render_nested_decl(
+                        &self.source_file,
+                        &format_declaration(&decl),
+                        &item.attrs,
+                    ))
+                }
+                TraitItem::Type(item) => {
+                    let decl = format!("type {};", item.ident);
+                    Some(render_nested_decl(
+                        &self.source_file,
+                        &format_declaration(&decl),
+                        &item.attrs,
+                    ))
+                }
+                _ => None,
+            })
+            .collect();
+
+        self.docs.push(Doc::Trait(TraitDoc {
+            ident,
+            doc: doc.text,
+            items,
+        }));
+
+        visit::visit_item_trait(self, node);
+    }
+
+    fn visit_item_impl(&mut self, node: &'ast ItemImpl) {
+        let self_ty = format_type(&node.self_ty);
+        let heading = match &node.trait_ {
+            Some((_, path, _)) => format!("impl {} for {}", format_path(path), self_ty),
+            None => format!("impl {}", self_ty),
+        };
+        let doc = format_doc(&node.attrs);
+        validate_doc_code_blocks(&self.source_file, &doc);
+
+        let items = node
+            .items
+            .iter()
+            .filter_map(|item| match item {
+                ImplItem::Method(method) => Some(render_nested_method(
+                    &self.source_file,
+                    &method.vis,
+                    &method.sig,
+                    &method.attrs,
+                )),
+                ImplItem::Const(item) => {
+                    let decl = format!(
+                        "{}const {}: {};",
+                        format_visibility(&item.vis),
+                        item.ident,
+                        format_type(&item.ty)
+                    );
+                    Some(render_nested_decl(
+                        &self.source_file,
+                        &format_declaration(&decl),
+                        &item.attrs,
+                    ))
+                }
+                ImplItem::Type(item) => {
+                    let decl = format!(
+                        "{}type {} = {};",
+                        format_visibility(&item.vis),
+                        item.ident,
+                        format_type(&item.ty)
+                    );
+                    Some(render_nested_decl(
+                        &self.source_file,
+                        &format_declaration(&decl),
+                        &item.attrs,
+                    ))
+                }
+                _ => None,
+            })
+            .collect();
+
+        self.docs.push(Doc::Impl(ImplDoc {
+            heading,
+            doc: doc.text,
+            items,
+        }));
+
+        visit::visit_item_impl(self, node);
+    }
 }
 
 /// This is just a test module to use for formatting!
@@ -215,6 +1032,144 @@ mod foo {
     fn foo() {}
 }
 
+/// A heading gathered while walking a document, nested under its enclosing
+/// headings the way rustdoc's `TocBuilder` nests `TocEntry`s.
+struct TocEntry {
+    level: u32,
+    text: String,
+    slug: String,
+    children: Vec<TocEntry>,
+}
+
+/// Deduplicates heading slugs by appending `-1`, `-2`, etc. to repeats,
+/// mirroring rustdoc's `IdMap`.
+struct IdMap {
+    used: HashMap<String, usize>,
+}
+
+impl IdMap {
+    fn new() -> Self {
+        IdMap {
+            used: HashMap::new(),
+        }
+    }
+
+    fn derive(&mut self, text: &str) -> String {
+        let base = slugify(text);
+        let count = self.used.entry(base.clone()).or_insert(0);
+        let slug = if *count == 0 {
+            base
+        } else {
+            format!("{}-{}", base, count)
+        };
+        *count += 1;
+        slug
+    }
+}
+
+/// Turns heading text into an anchor-friendly slug: lowercase, non-alphanumeric
+/// runs collapsed to a single `-`.
+fn slugify(text: &str) -> String {
+    let mut slug = String::new();
+    let mut last_was_dash = true;
+    for ch in text.chars() {
+        if ch.is_alphanumeric() {
+            slug.push(ch.to_ascii_lowercase());
+            last_was_dash = false;
+        } else if !last_was_dash {
+            slug.push('-');
+            last_was_dash = true;
+        }
+    }
+    slug.trim_end_matches('-').to_string()
+}
+
+/// Collects each heading's level and text, in document order.
+fn collect_headings(input: &str) -> Vec<(u32, String)> {
+    let parser = Parser::new_ext(input, opts());
+
+    let mut headings = Vec::new();
+    let mut current: Option<(u32, String)> = None;
+
+    for event in parser {
+        match event {
+            Event::Start(Tag::Heading(level)) => current = Some((level, String::new())),
+            Event::End(Tag::Heading(_)) => {
+                if let Some(heading) = current.take() {
+                    headings.push(heading);
+                }
+            }
+            Event::Text(text) | Event::Code(text) => {
+                if let Some((_, buf)) = current.as_mut() {
+                    buf.push_str(&text);
+                }
+            }
+            _ => {}
+        }
+    }
+    headings
+}
+
+/// Nests a flat, in-order list of headings by level, the way rustdoc's
+/// `TocBuilder` builds a `Toc` while walking a document: an entry attaches as
+/// a child of the nearest still-open ancestor with a smaller level.
+fn build_toc(headings: Vec<(u32, String)>) -> Vec<TocEntry> {
+    let mut ids = IdMap::new();
+    let root = TocEntry {
+        level: 0,
+        text: String::new(),
+        slug: String::new(),
+        children: Vec::new(),
+    };
+    let mut stack = vec![root];
+
+    for (level, text) in headings {
+        let slug = ids.derive(&text);
+        let entry = TocEntry {
+            level,
+            text,
+            slug,
+            children: Vec::new(),
+        };
+
+        while stack.len() > 1 && stack.last().unwrap().level >= level {
+            let finished = stack.pop().unwrap();
+            stack.last_mut().unwrap().children.push(finished);
+        }
+        stack.push(entry);
+    }
+
+    while stack.len() > 1 {
+        let finished = stack.pop().unwrap();
+        stack.last_mut().unwrap().children.push(finished);
+    }
+
+    stack.pop().unwrap().children
+}
+
+/// Renders a TOC as an indented outline, e.g. for printing above a long
+/// document's rendered markdown.
+fn render_toc(entries: &[TocEntry]) -> String {
+    let mut out = String::new();
+    render_toc_level(entries, 0, &mut out);
+    out
+}
+
+fn render_toc_level(entries: &[TocEntry], depth: usize, out: &mut String) {
+    for entry in entries {
+        writeln!(
+            out,
+            "{:indent$}- {} (#{})",
+            "",
+            entry.text,
+            entry.slug,
+            indent = depth * 2
+        )
+        .unwrap();
+        render_toc_level(&entry.children, depth + 1, out);
+    }
+}
+
 /// Hello, this is the main doc!
 ///
 /// ## Examples
@@ -232,23 +1187,123 @@ mod foo {
 ///     }
 ///
 fn main() -> Result<(), Box<dyn Error>> {
-    let mut file = fs::File::open(
-        std::env::args()
-            .skip(1)
-            .next()
-            .expect("no filename provided"),
-    )?;
+    let mut markdown_flag = false;
+    let mut toc_flag = false;
+    let mut theme_flag = None;
+    let mut source_file = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--markdown" => markdown_flag = true,
+            "--toc" => toc_flag = true,
+            "--theme" => theme_flag = Some(args.next().expect("--theme requires a value")),
+            _ => source_file = Some(arg),
+        }
+    }
+    let source_file = source_file.expect("no filename provided");
+
+    let theme = theme_flag
+        .or_else(|| std::env::var("RUSTDOC_CLI_THEME").ok())
+        .unwrap_or_else(|| DEFAULT_THEME.to_string());
+    if let Err(err) = set_theme(theme) {
+        eprintln!("error: {}", err);
+        std::process::exit(1);
+    }
+
+    let mut file = fs::File::open(&source_file)?;
     let mut content = String::new();
     file.read_to_string(&mut content)?;
 
+    // A `.md` file (or an explicit `--markdown`) is rendered directly,
+    // turning the tool into a general terminal markdown viewer rather than
+    // only a Rust doc extractor.
+    if markdown_flag || source_file.ends_with(".md") {
+        if toc_flag {
+            let toc = build_toc(collect_headings(&content));
+            print!("{}\n\n", render_toc(&toc));
+        }
+        print!("{}", format_markdown(&content));
+        return Ok(());
+    }
+
     let ast = syn::parse_file(&content)?;
 
-    let mut visitor = Visitor { docs: Vec::new() };
+    let mut visitor = Visitor {
+        docs: Vec::new(),
+        source_file,
+    };
     visitor.visit_file(&ast);
 
+    if toc_flag {
+        let headings = visitor
+            .docs
+            .iter()
+            .flat_map(|doc| collect_headings(doc.doc_text()))
+            .collect();
+        print!("{}\n\n", render_toc(&build_toc(headings)));
+    }
+
     for doc in visitor.docs {
         print!("{}", doc);
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slugify_collapses_non_alphanumeric_runs() {
+        assert_eq!(slugify("Hello, World!"), "hello-world");
+        assert_eq!(slugify("  leading and trailing  "), "leading-and-trailing");
+        assert_eq!(slugify("already-a-slug"), "already-a-slug");
+    }
+
+    #[test]
+    fn id_map_dedupes_repeated_slugs() {
+        let mut ids = IdMap::new();
+        assert_eq!(ids.derive("Examples"), "examples");
+        assert_eq!(ids.derive("Examples"), "examples-1");
+        assert_eq!(ids.derive("Examples"), "examples-2");
+        assert_eq!(ids.derive("Other"), "other");
+    }
+
+    #[test]
+    fn build_toc_nests_by_heading_level() {
+        let headings = vec![
+            (1, "Intro".to_string()),
+            (2, "Setup".to_string()),
+            (3, "Prerequisites".to_string()),
+            (2, "Usage".to_string()),
+        ];
+        let toc = build_toc(headings);
+
+        assert_eq!(toc.len(), 1);
+        assert_eq!(toc[0].text, "Intro");
+        assert_eq!(toc[0].children.len(), 2);
+        assert_eq!(toc[0].children[0].text, "Setup");
+        assert_eq!(toc[0].children[0].children[0].text, "Prerequisites");
+        assert_eq!(toc[0].children[1].text, "Usage");
+        assert!(toc[0].children[1].children.is_empty());
+    }
+
+    #[test]
+    fn code_block_language_strips_rustdoc_directives() {
+        assert_eq!(code_block_language("rust,should_panic"), None);
+        assert_eq!(code_block_language("ignore"), None);
+        assert_eq!(code_block_language(""), None);
+        assert_eq!(code_block_language("toml,ignore"), Some("toml".to_string()));
+        assert_eq!(code_block_language("bash"), Some("bash".to_string()));
+    }
+
+    #[test]
+    fn strip_hidden_lines_removes_hash_prefixed_lines() {
+        let code = "# hidden_setup();\nvisible_line();\n## literal_hash();\n#\nfinal_line();\n";
+        assert_eq!(
+            strip_hidden_lines(code),
+            "visible_line();\n# literal_hash();\nfinal_line();\n"
+        );
+    }
+}